@@ -0,0 +1,182 @@
+//! Transparent decompression for gzip/zstd measurement dumps.
+//!
+//! Neither format supports the random-access reads the mmap and
+//! `read_exact_at` backends rely on, so compressed input takes a different
+//! shape entirely: one reader thread decodes the stream sequentially and
+//! hands off fixed-size, newline-aligned blocks to a pool of parser threads
+//! over a bounded channel. Parsing itself is unchanged -- every backend
+//! folds rows through [`crate::io::process_lines`].
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io::Read;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+use crate::io::{self, RunStats};
+use crate::validate::DefectStats;
+use crate::Record;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Compression {
+    Gzip,
+    Zstd,
+}
+
+/// Sniff the first few bytes of `path` for a gzip or zstd magic number.
+/// Returns `Ok(None)` for anything else, including a file too short to hold
+/// one.
+pub(crate) fn detect(path: &str) -> std::io::Result<Option<Compression>> {
+    let mut file = File::open(path)?;
+    let mut magic = [0u8; 4];
+    let mut filled = 0usize;
+    while filled < magic.len() {
+        match file.read(&mut magic[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    if filled >= GZIP_MAGIC.len() && magic[..GZIP_MAGIC.len()] == GZIP_MAGIC {
+        return Ok(Some(Compression::Gzip));
+    }
+    if filled >= ZSTD_MAGIC.len() && magic == ZSTD_MAGIC {
+        return Ok(Some(Compression::Zstd));
+    }
+    Ok(None)
+}
+
+/// Decode `path` through the matching decompressor and feed the existing
+/// worker pool over a bounded channel, so the only thing that changes versus
+/// the uncompressed paths is where the bytes come from.
+pub(crate) fn aggregate<S>(
+    path: &str,
+    compression: Compression,
+    thread_count: usize,
+    granule_size: usize,
+    lenient: bool,
+) -> (HashMap<String, Record, S>, DefectStats, RunStats)
+where
+    S: BuildHasher + Default + Send + Sync,
+{
+    let file = File::open(path).unwrap();
+    let reader: Box<dyn Read + Send> = match compression {
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::stream::Decoder::new(file).unwrap()),
+    };
+
+    // Bounded so the single-threaded decoder can't race arbitrarily far
+    // ahead of the parser pool and blow up memory on a fast disk / slow CPU.
+    let (tx, rx) = mpsc::sync_channel::<(usize, Vec<u8>)>(thread_count * 2);
+    let rx = Arc::new(Mutex::new(rx));
+
+    let outer_map: Arc<Mutex<HashMap<String, Record, S>>> =
+        Arc::new(Mutex::new(HashMap::with_hasher(S::default())));
+    let defect_state = Arc::new(Mutex::new(DefectStats::default()));
+    let stats_state = Arc::new(Mutex::new(RunStats::default()));
+
+    std::thread::scope(|scope| {
+        scope.spawn(move || split_into_blocks(reader, granule_size, tx));
+
+        for _ in 0..thread_count {
+            let rx = rx.clone();
+            let outer_map = outer_map.clone();
+            let defect_state = defect_state.clone();
+            let stats_state = stats_state.clone();
+            scope.spawn(move || loop {
+                let next = rx.lock().unwrap().recv();
+                let Ok((offset, block)) = next else {
+                    break;
+                };
+                let (local, defects) = io::process_lines::<S>(&block, offset, lenient);
+
+                let mut lock = outer_map.lock().unwrap();
+                for (city, record) in local {
+                    let city = String::from_utf8_lossy(city).to_string();
+                    lock.entry(city).and_modify(|r| r.merge(record)).or_insert(record);
+                }
+                drop(lock);
+                defect_state.lock().unwrap().merge(defects);
+                stats_state.lock().unwrap().record(block.len());
+            });
+        }
+    });
+
+    let outer_map = Arc::into_inner(outer_map).unwrap().into_inner().unwrap();
+    let defects = Arc::into_inner(defect_state).unwrap().into_inner().unwrap();
+    let stats = Arc::into_inner(stats_state).unwrap().into_inner().unwrap();
+    (outer_map, defects, stats)
+}
+
+/// Read the decompressed stream in `granule_size`-ish bursts, splitting at
+/// the last newline in whatever's accumulated so every block handed to `tx`
+/// ends on a row boundary. The trailing partial row, if any, carries over
+/// into the next read.
+fn split_into_blocks(mut reader: Box<dyn Read + Send>, granule_size: usize, tx: mpsc::SyncSender<(usize, Vec<u8>)>) {
+    let mut carry: Vec<u8> = Vec::new();
+    let mut read_buf = vec![0u8; granule_size];
+    let mut consumed = 0usize;
+
+    loop {
+        let n = reader.read(&mut read_buf).unwrap();
+        if n == 0 {
+            break;
+        }
+        carry.extend_from_slice(&read_buf[..n]);
+        if carry.len() < granule_size {
+            continue;
+        }
+        let Some(last_newline) = carry.iter().rposition(|&b| b == b'\n') else {
+            continue;
+        };
+        let remainder = carry.split_off(last_newline + 1);
+        let block = std::mem::replace(&mut carry, remainder);
+        let offset = consumed;
+        consumed += block.len();
+        if tx.send((offset, block)).is_err() {
+            return;
+        }
+    }
+
+    if !carry.is_empty() {
+        let _ = tx.send((consumed, carry));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn split_into_blocks_reassembles_exactly_and_tracks_offsets() {
+        let data = b"aa\nbb\ncc\ndd\n".to_vec();
+        let reader: Box<dyn Read + Send> = Box::new(Cursor::new(data.clone()));
+        let (tx, rx) = mpsc::sync_channel(8);
+
+        split_into_blocks(reader, 5, tx);
+        let blocks: Vec<(usize, Vec<u8>)> = rx.iter().collect();
+
+        let mut rebuilt = Vec::new();
+        for (offset, block) in &blocks {
+            assert_eq!(*offset, rebuilt.len());
+            rebuilt.extend_from_slice(block);
+        }
+        assert_eq!(rebuilt, data);
+    }
+
+    #[test]
+    fn split_into_blocks_flushes_a_trailing_partial_row() {
+        let data = b"aa\nb".to_vec();
+        let reader: Box<dyn Read + Send> = Box::new(Cursor::new(data.clone()));
+        let (tx, rx) = mpsc::sync_channel(8);
+
+        split_into_blocks(reader, 1024, tx);
+        let blocks: Vec<(usize, Vec<u8>)> = rx.iter().collect();
+
+        assert_eq!(blocks, vec![(0, data)]);
+    }
+}
@@ -0,0 +1,183 @@
+use std::hash::{BuildHasherDefault, Hasher};
+use std::ops::BitXor;
+
+// This is basically a straight copy of the FxHasher from the rustc crate.
+// Was just curious about how the hashing internals worked. Let me live.
+#[cfg(not(feature = "xxhash"))]
+const KEY: usize = 0x517c_c1b7_2722_0a95;
+
+#[cfg(not(feature = "xxhash"))]
+#[derive(Default)]
+pub struct FastHasher {
+    hash: usize,
+}
+
+#[cfg(not(feature = "xxhash"))]
+impl FastHasher {
+    fn compute_hash(&mut self, int: usize) {
+        self.hash = self.hash.rotate_left(5).bitxor(int).wrapping_mul(KEY)
+    }
+}
+
+#[cfg(not(feature = "xxhash"))]
+impl Hasher for FastHasher {
+    fn write(&mut self, mut bytes: &[u8]) {
+        while bytes.len() >= 8 {
+            let qword: [u8; 8] = bytes[0..8].try_into().unwrap();
+            let qword = usize::from_ne_bytes(qword);
+            self.compute_hash(qword);
+            bytes = &bytes[8..];
+        }
+
+        if bytes.len() >= 4 {
+            let dword: [u8; 4] = bytes[0..4].try_into().unwrap();
+            let dword = u32::from_ne_bytes(dword) as usize;
+            self.compute_hash(dword);
+            bytes = &bytes[4..];
+        }
+
+        if bytes.len() >= 2 {
+            let word: [u8; 2] = bytes[0..2].try_into().unwrap();
+            let word = u16::from_ne_bytes(word) as usize;
+            self.compute_hash(word);
+            bytes = &bytes[2..];
+        }
+
+        if let Some(byte) = bytes.first() {
+            self.compute_hash(*byte as usize);
+        }
+    }
+    fn finish(&self) -> u64 {
+        self.hash as u64
+    }
+}
+
+#[cfg(not(feature = "xxhash"))]
+pub type BuildFastHasher = BuildHasherDefault<FastHasher>;
+
+// XXH3-style short-key hashing, ported from the reference algorithm. The
+// point of pulling this in is the finalizing avalanche: FxHasher's
+// rotate-xor-multiply leaves the high bits under-mixed, which shows up as
+// extra collisions on short, similar-prefix keys (city names share long
+// common prefixes a lot more than random text does).
+#[cfg(feature = "xxhash")]
+const PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+#[cfg(feature = "xxhash")]
+const PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+#[cfg(feature = "xxhash")]
+const PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+#[cfg(feature = "xxhash")]
+const PRIME64_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+#[cfg(feature = "xxhash")]
+#[inline(always)]
+fn widening_mul(a: u64, b: u64) -> (u64, u64) {
+    let full = (a as u128) * (b as u128);
+    (full as u64, (full >> 64) as u64)
+}
+
+#[cfg(feature = "xxhash")]
+#[inline(always)]
+fn avalanche(mut h: u64) -> u64 {
+    h ^= h >> 37;
+    h = h.wrapping_mul(PRIME64_3);
+    h ^= h >> 32;
+    h
+}
+
+#[cfg(feature = "xxhash")]
+#[derive(Default)]
+pub struct XxHasher {
+    hash: u64,
+}
+
+#[cfg(feature = "xxhash")]
+impl XxHasher {
+    // 4..=8 bytes: fold the first and last 4 bytes into one 64-bit lane so
+    // every byte is covered; the two reads overlap for keys shorter than 8.
+    fn hash_4_to_8(bytes: &[u8]) -> u64 {
+        let lo = u32::from_ne_bytes(bytes[0..4].try_into().unwrap()) as u64;
+        let hi = u32::from_ne_bytes(bytes[bytes.len() - 4..].try_into().unwrap()) as u64;
+        let combined = lo | (hi << 32);
+        avalanche(combined.wrapping_mul(PRIME64_1))
+    }
+
+    // 9..=16 bytes: two 64-bit lanes from the ends, multiply-folded together.
+    fn hash_9_to_16(bytes: &[u8]) -> u64 {
+        let lo = u64::from_ne_bytes(bytes[0..8].try_into().unwrap());
+        let hi = u64::from_ne_bytes(bytes[bytes.len() - 8..].try_into().unwrap());
+        let (m_lo, m_hi) = widening_mul(lo ^ PRIME64_1, hi ^ PRIME64_2);
+        avalanche(m_lo.bitxor(m_hi).wrapping_add(PRIME64_5))
+    }
+
+    // 17..=128 bytes: walk 16-byte blocks in from both ends, accumulating
+    // multiply-fold results into the running state. `front`/`back` are
+    // cursors into the same slice, not independent copies, so the window
+    // between them actually shrinks each iteration.
+    fn hash_17_to_128(bytes: &[u8]) -> u64 {
+        let mut state = PRIME64_5.wrapping_add(bytes.len() as u64);
+        let mut front = 0usize;
+        let mut back = bytes.len();
+        while front + 16 <= back {
+            let f_lo = u64::from_ne_bytes(bytes[front..front + 8].try_into().unwrap());
+            let f_hi = u64::from_ne_bytes(bytes[front + 8..front + 16].try_into().unwrap());
+            let b_lo = u64::from_ne_bytes(bytes[back - 16..back - 8].try_into().unwrap());
+            let b_hi = u64::from_ne_bytes(bytes[back - 8..back].try_into().unwrap());
+
+            let (lo, hi) = widening_mul(f_lo ^ PRIME64_1, b_hi ^ PRIME64_2);
+            state = state.wrapping_add(lo.bitxor(hi));
+            let (lo, hi) = widening_mul(f_hi ^ PRIME64_1, b_lo ^ PRIME64_2);
+            state = state.wrapping_add(lo.bitxor(hi));
+
+            front += 16;
+            back -= 16;
+        }
+        avalanche(state)
+    }
+}
+
+#[cfg(feature = "xxhash")]
+impl Hasher for XxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.hash ^= match bytes.len() {
+            0..=3 => {
+                let mut acc = PRIME64_5;
+                for &b in bytes {
+                    acc = acc.wrapping_add((b as u64).wrapping_mul(PRIME64_5));
+                    acc = acc.rotate_left(11).wrapping_mul(PRIME64_1);
+                }
+                avalanche(acc)
+            }
+            4..=8 => Self::hash_4_to_8(bytes),
+            9..=16 => Self::hash_9_to_16(bytes),
+            17..=128 => Self::hash_17_to_128(bytes),
+            _ => bytes
+                .chunks(128)
+                .fold(PRIME64_5, |acc, block| acc ^ Self::hash_17_to_128(block)),
+        };
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+#[cfg(feature = "xxhash")]
+pub type BuildXxHasher = BuildHasherDefault<XxHasher>;
+
+#[cfg(feature = "xxhash")]
+pub type BuildDefaultHasher = BuildXxHasher;
+#[cfg(not(feature = "xxhash"))]
+pub type BuildDefaultHasher = BuildFastHasher;
+
+#[cfg(all(test, feature = "xxhash"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_17_to_128_distinguishes_equal_length_inputs() {
+        let a = XxHasher::hash_17_to_128(b"AAAAAAAAAAAAAAAAAAAA");
+        let b = XxHasher::hash_17_to_128(b"BBBBBBBBBBBBBBBBBBBB");
+        assert_ne!(a, b);
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+use crate::compress;
+use crate::validate::{self, DefectStats};
+use crate::{parse_row, InnerMap, Record};
+
+/// Run the full chunk-and-aggregate pipeline over `path` using `thread_count`
+/// workers, returning one merged record per city, a summary of any rows
+/// `lenient` chose to skip, and per-chunk throughput stats.
+///
+/// Compressed inputs (gzip, zstd) are detected by magic bytes and streamed
+/// through sequentially via [`compress::aggregate`], since they don't support
+/// the random-access reads the other two paths rely on. Otherwise, on Unix
+/// this maps the file once and hands each worker a zero-copy slice of it,
+/// reduced in parallel with rayon; everywhere else `FileExt::read_exact_at`
+/// isn't available, so we fall back to a copying path in which `thread_count`
+/// threads repeatedly claim granule-sized chunks from a shared offset.
+pub fn aggregate<S>(
+    path: &str,
+    thread_count: usize,
+    granule_size: usize,
+    lenient: bool,
+) -> (HashMap<String, Record, S>, DefectStats, RunStats)
+where
+    S: BuildHasher + Default + Send + Sync,
+{
+    if let Some(compression) = compress::detect(path).unwrap() {
+        return compress::aggregate(path, compression, thread_count, granule_size, lenient);
+    }
+
+    let file = File::open(path).unwrap();
+    let file_size = file.metadata().unwrap().len() as usize;
+
+    #[cfg(unix)]
+    {
+        mmap::aggregate(&file, file_size, thread_count, granule_size, lenient)
+    }
+    #[cfg(not(unix))]
+    {
+        copy::aggregate(&file, file_size, thread_count, granule_size, lenient)
+    }
+}
+
+/// Parse newline-separated rows out of `data`, folding each into a
+/// per-thread map. `base_offset` is `data`'s absolute position in the
+/// original (decompressed) input, used only for defect offset reporting.
+/// Shared by the mmap, copying, and streaming-decompression backends so they
+/// all aggregate identically; they differ only in how they get `data`.
+pub(crate) fn process_lines<'d, S: BuildHasher + Default>(
+    data: &'d [u8],
+    base_offset: usize,
+    lenient: bool,
+) -> (InnerMap<'d, S>, DefectStats) {
+    let mut local: InnerMap<S> = HashMap::with_hasher(S::default());
+    let mut defects = DefectStats::default();
+    let mut pos = 0usize;
+    while pos < data.len() {
+        let rel_end = data[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map(|i| pos + i)
+            .unwrap_or(data.len());
+        let line = &data[pos..rel_end];
+        if !line.is_empty() {
+            ingest_line(&mut local, &mut defects, line, base_offset + pos, lenient);
+        }
+        pos = rel_end + 1;
+    }
+    (local, defects)
+}
+
+/// Per-chunk effective sizes (after newline trimming), used to print a
+/// chunker comparison report: how many chunks a run produced and how evenly
+/// sized they ended up, which is what you'd tune `granule_size` against.
+#[derive(Debug, Default)]
+pub(crate) struct RunStats {
+    chunk_sizes: Vec<usize>,
+}
+
+impl RunStats {
+    pub(crate) fn record(&mut self, size: usize) {
+        self.chunk_sizes.push(size);
+    }
+
+    pub(crate) fn merge(&mut self, other: RunStats) {
+        self.chunk_sizes.extend(other.chunk_sizes);
+    }
+
+    pub(crate) fn report(&self, elapsed: Duration) {
+        let chunks = self.chunk_sizes.len();
+        if chunks == 0 {
+            return;
+        }
+        let total: usize = self.chunk_sizes.iter().sum();
+        let mean = total as f64 / chunks as f64;
+        let variance = self
+            .chunk_sizes
+            .iter()
+            .map(|&size| {
+                let delta = size as f64 - mean;
+                delta * delta
+            })
+            .sum::<f64>()
+            / chunks as f64;
+        let stddev = variance.sqrt();
+        let secs = elapsed.as_secs_f64();
+        let throughput_mib_s = if secs > 0.0 {
+            (total as f64 / (1024.0 * 1024.0)) / secs
+        } else {
+            0.0
+        };
+
+        eprintln!("chunk report:");
+        eprintln!("  chunks processed  : {chunks}");
+        eprintln!("  mean chunk size   : {mean:.0} B");
+        eprintln!("  chunk size stddev : {stddev:.0} B");
+        eprintln!("  throughput        : {throughput_mib_s:.2} MiB/s");
+        eprintln!("  wall time         : {secs:.3} s");
+    }
+}
+
+/// Parse one line into the local map, either trusting the input (fast path)
+/// or classifying and recording the defect (`--lenient`). `offset` is the
+/// line's absolute position in the (decompressed) input, used only for defect
+/// reporting.
+fn ingest_line<'thread, S: BuildHasher + Default>(
+    local: &mut InnerMap<'thread, S>,
+    defects: &mut DefectStats,
+    line: &'thread [u8],
+    offset: usize,
+    lenient: bool,
+) {
+    if lenient {
+        match validate::try_parse_row(line) {
+            Ok((city, measurement)) => {
+                local
+                    .entry(city)
+                    .and_modify(|r| r.update(measurement))
+                    .or_insert(Record::new(measurement));
+            }
+            Err(defect) => defects.record(defect, offset),
+        }
+    } else {
+        let (city, measurement) = parse_row(line);
+        local
+            .entry(city)
+            .and_modify(|r| r.update(measurement))
+            .or_insert(Record::new(measurement));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::RowDefect;
+    use std::collections::hash_map::RandomState;
+
+    #[test]
+    fn process_lines_merges_duplicate_cities() {
+        let data = b"Paris;12.3\nParis;20.1\nLondon;-4.5\n";
+        let (local, defects) = process_lines::<RandomState>(data, 0, false);
+
+        assert_eq!(local.len(), 2);
+        let paris = local[&b"Paris"[..]];
+        assert_eq!(paris.count, 2);
+        let london = local[&b"London"[..]];
+        assert_eq!(london.count, 1);
+        assert_eq!(defects, DefectStats::default());
+    }
+
+    #[test]
+    fn process_lines_ignores_blank_lines() {
+        let data = b"\nLondon;-4.5\n";
+        let (local, _) = process_lines::<RandomState>(data, 100, false);
+        assert_eq!(local.len(), 1);
+        assert!(local.contains_key(&b"London"[..]));
+    }
+
+    #[test]
+    fn process_lines_in_lenient_mode_records_defects_instead_of_panicking() {
+        let data = b"London;-4.5\nnot a row\n";
+        let (local, defects) = process_lines::<RandomState>(data, 0, true);
+
+        assert_eq!(local.len(), 1);
+        let mut expected = DefectStats::default();
+        expected.record(RowDefect::MissingSeparator, 12);
+        assert_eq!(defects, expected);
+    }
+}
+
+#[cfg(unix)]
+mod mmap {
+    use super::*;
+    use memmap2::Mmap;
+    use rayon::prelude::*;
+
+    pub fn aggregate<S>(
+        file: &File,
+        file_size: usize,
+        thread_count: usize,
+        granule_size: usize,
+        lenient: bool,
+    ) -> (HashMap<String, Record, S>, DefectStats, RunStats)
+    where
+        S: BuildHasher + Default + Send + Sync,
+    {
+        // Safety: `measurements.txt` is not expected to be modified by another
+        // process while this tool is running.
+        let mmap = unsafe { Mmap::map(file).unwrap() };
+        let bounds = chunk_bounds(&mmap, file_size, granule_size);
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(thread_count)
+            .build()
+            .unwrap();
+
+        let (merged, defects, stats): (InnerMap<S>, DefectStats, RunStats) = pool.install(|| {
+            bounds
+                .into_par_iter()
+                .map(|(start, end)| {
+                    let slice = &mmap[start..end];
+                    let (local, defects) = process_lines(slice, start, lenient);
+                    let mut stats = RunStats::default();
+                    stats.record(slice.len());
+                    (local, defects, stats)
+                })
+                .reduce(
+                    || (HashMap::with_hasher(S::default()), DefectStats::default(), RunStats::default()),
+                    |(mut a_map, mut a_defects, mut a_stats), (b_map, b_defects, b_stats)| {
+                        for (city, record) in b_map {
+                            a_map.entry(city).and_modify(|r| r.merge(record)).or_insert(record);
+                        }
+                        a_defects.merge(b_defects);
+                        a_stats.merge(b_stats);
+                        (a_map, a_defects, a_stats)
+                    },
+                )
+        });
+
+        // One sequential pass to turn the borrowed-key map into owned
+        // `String` keys. This replaces the old per-chunk `Mutex<OuterMap>`
+        // lock: there's now a single fold instead of one lock acquisition per
+        // chunk.
+        let mut outer: HashMap<String, Record, S> = HashMap::with_hasher(S::default());
+        for (city, record) in merged {
+            let city = String::from_utf8_lossy(city).to_string();
+            outer
+                .entry(city)
+                .and_modify(|r| r.merge(record))
+                .or_insert(record);
+        }
+        (outer, defects, stats)
+    }
+
+    /// Walk `mmap[..file_size]` in `granule_size` strides, snapping each
+    /// boundary forward to the next `\n`, producing many small
+    /// newline-aligned slices instead of exactly `thread_count` large ones.
+    /// Rayon's work-stealing scheduler then keeps fast cores fed with more
+    /// chunks rather than stalling everyone behind one oversized chunk.
+    fn chunk_bounds(mmap: &[u8], file_size: usize, granule_size: usize) -> Vec<(usize, usize)> {
+        let mut bounds = Vec::with_capacity(file_size / granule_size + 1);
+        let mut start = 0usize;
+        while start < file_size {
+            let nominal_end = (start + granule_size).min(file_size);
+            let end = if nominal_end >= file_size {
+                file_size
+            } else {
+                match mmap[nominal_end..].iter().position(|&b| b == b'\n') {
+                    Some(rel) => nominal_end + rel,
+                    None => file_size,
+                }
+            };
+            if end > start {
+                bounds.push((start, end));
+            }
+            start = end + 1;
+        }
+        bounds
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn chunk_bounds_snaps_boundaries_to_newlines_and_covers_every_row() {
+            let data = b"aa\nbb\ncc\ndd\n";
+            let bounds = chunk_bounds(data, data.len(), 5);
+
+            for &(_, end) in &bounds {
+                assert!(end == data.len() || data[end] == b'\n');
+            }
+            let row_count: usize = bounds
+                .iter()
+                .map(|&(start, end)| {
+                    data[start..end].split(|&b| b == b'\n').filter(|line| !line.is_empty()).count()
+                })
+                .sum();
+            assert_eq!(row_count, 4);
+        }
+
+        #[test]
+        fn chunk_bounds_on_empty_file_yields_no_bounds() {
+            assert_eq!(chunk_bounds(b"", 0, 5), Vec::new());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod copy {
+    use super::*;
+    use crate::OuterMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    type DefectState = Arc<Mutex<DefectStats>>;
+    type StatsState = Arc<Mutex<RunStats>>;
+
+    pub fn aggregate<S>(
+        file: &File,
+        file_size: usize,
+        thread_count: usize,
+        granule_size: usize,
+        lenient: bool,
+    ) -> (HashMap<String, Record, S>, DefectStats, RunStats)
+    where
+        S: BuildHasher + Default + Send + Sync,
+    {
+        let offset = Arc::new(AtomicUsize::new(0));
+        let outer_map: OuterMap<S> = Arc::new(Mutex::new(HashMap::with_hasher(S::default())));
+        let defect_state: DefectState = Arc::new(Mutex::new(DefectStats::default()));
+        let stats_state: StatsState = Arc::new(Mutex::new(RunStats::default()));
+
+        std::thread::scope(|scope| {
+            for _ in 0..thread_count {
+                let offset = offset.clone();
+                let outer_map = outer_map.clone();
+                let defect_state = defect_state.clone();
+                let stats_state = stats_state.clone();
+                scope.spawn(move || loop {
+                    let start = offset.fetch_add(granule_size, Ordering::SeqCst);
+                    if start >= file_size {
+                        break;
+                    }
+                    parse_chunk(
+                        file,
+                        file_size,
+                        granule_size,
+                        start,
+                        &outer_map,
+                        &defect_state,
+                        &stats_state,
+                        lenient,
+                    );
+                });
+            }
+        });
+
+        let outer_map = Arc::into_inner(outer_map).unwrap().into_inner().unwrap();
+        let defects = Arc::into_inner(defect_state).unwrap().into_inner().unwrap();
+        let stats = Arc::into_inner(stats_state).unwrap().into_inner().unwrap();
+        (outer_map, defects, stats)
+    }
+
+    /// Reads one granule, returning the trimmed buffer and the absolute file
+    /// offset of its first byte (needed to report defect offsets).
+    fn read_file_chunk(file: &File, file_size: usize, granule_size: usize, offset: usize) -> (Vec<u8>, usize) {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let (file_i, buf_size, bytes_excess) = match offset == 0 {
+            true => (offset as u64, granule_size.min(file_size), 0),
+            false => {
+                let file_i = (offset - 64) as u64;
+                let bytes_excess = 64;
+                let buf_size = (granule_size + 64).min(file_size - offset + 64);
+                (file_i, buf_size, bytes_excess)
+            }
+        };
+        let mut buf: Vec<u8> = vec![0; buf_size];
+        let mut file = file.try_clone().unwrap();
+        file.seek(SeekFrom::Start(file_i)).unwrap();
+        file.read_exact(&mut buf).unwrap();
+        let mut absolute_start = file_i as usize;
+        // trim head
+        for i in 0..bytes_excess {
+            if buf[i] == b'\n' {
+                buf.drain(..=i);
+                absolute_start += i + 1;
+                break;
+            }
+        }
+        // trim tail
+        let tail_i = buf.len() - 1;
+        for i in ((tail_i - 64)..tail_i).rev() {
+            if buf[i] == b'\n' {
+                buf.truncate(i);
+                break;
+            }
+        }
+        (buf, absolute_start)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn parse_chunk<S: BuildHasher + Default>(
+        file: &File,
+        file_size: usize,
+        granule_size: usize,
+        offset: usize,
+        outer_map: &OuterMap<S>,
+        defect_state: &DefectState,
+        stats_state: &StatsState,
+        lenient: bool,
+    ) {
+        let (buf, absolute_start) = read_file_chunk(file, file_size, granule_size, offset);
+        let (local_map, defects) = process_lines(&buf, absolute_start, lenient);
+
+        let mut lock = outer_map.lock().unwrap();
+        for (city, record) in local_map.into_iter() {
+            let city = String::from_utf8_lossy(city).to_string();
+            lock.entry(city).and_modify(|r| r.merge(record)).or_insert(record);
+        }
+        drop(lock);
+        defect_state.lock().unwrap().merge(defects);
+        stats_state.lock().unwrap().record(buf.len());
+    }
+}
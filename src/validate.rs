@@ -0,0 +1,230 @@
+//! Lenient-mode row validation. `parse_row`/`parse_float` in `main` assume
+//! well-formed input and panic otherwise; the functions here classify the
+//! same defects instead of panicking, so a `--lenient` run can skip bad rows
+//! and still report what it dropped.
+
+/// A row that `--lenient` chose to skip, and why.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RowDefect {
+    MissingSeparator,
+    EmptyCity,
+    InvalidMeasurement,
+    LineTooLong,
+}
+
+impl RowDefect {
+    const ALL: [RowDefect; 4] = [
+        RowDefect::MissingSeparator,
+        RowDefect::EmptyCity,
+        RowDefect::InvalidMeasurement,
+        RowDefect::LineTooLong,
+    ];
+
+    fn index(self) -> usize {
+        match self {
+            RowDefect::MissingSeparator => 0,
+            RowDefect::EmptyCity => 1,
+            RowDefect::InvalidMeasurement => 2,
+            RowDefect::LineTooLong => 3,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            RowDefect::MissingSeparator => "missing ';' separator",
+            RowDefect::EmptyCity => "empty city name",
+            RowDefect::InvalidMeasurement => "non-numeric or out-of-range measurement",
+            RowDefect::LineTooLong => "line longer than the boundary-scan window",
+        }
+    }
+}
+
+// Past this, a "row" is almost certainly two lines glued together by a
+// missed newline rather than a single oversized-but-valid measurement.
+const MAX_ROW_LEN: usize = 128;
+const MAX_REPORTED_OFFSETS: usize = 8;
+
+/// Per-category defect counts plus a handful of offending byte offsets,
+/// collected by one worker and folded into a single end-of-run summary.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct DefectStats {
+    counts: [usize; RowDefect::ALL.len()],
+    offsets: Vec<(RowDefect, usize)>,
+}
+
+impl DefectStats {
+    pub(crate) fn record(&mut self, defect: RowDefect, offset: usize) {
+        self.counts[defect.index()] += 1;
+        if self.offsets.len() < MAX_REPORTED_OFFSETS {
+            self.offsets.push((defect, offset));
+        }
+    }
+
+    pub(crate) fn merge(&mut self, other: DefectStats) {
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts) {
+            *count += other_count;
+        }
+        self.offsets.extend(other.offsets);
+        self.offsets.truncate(MAX_REPORTED_OFFSETS);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.counts.iter().all(|&count| count == 0)
+    }
+
+    /// Print a compact per-category summary to stderr, mirroring the kind of
+    /// report a region/chunk validator would print for corrupted entries.
+    pub(crate) fn report(&self) {
+        if self.is_empty() {
+            return;
+        }
+        eprintln!("lenient mode: skipped malformed rows");
+        for defect in RowDefect::ALL {
+            let count = self.counts[defect.index()];
+            if count > 0 {
+                eprintln!("  {count:>8}  {}", defect.label());
+            }
+        }
+        if !self.offsets.is_empty() {
+            eprintln!("first offending byte offsets:");
+            for (defect, offset) in &self.offsets {
+                eprintln!("  {offset:>12}  {}", defect.label());
+            }
+        }
+    }
+}
+
+fn check_dot(x: &[u8], pos: usize) -> Result<(), RowDefect> {
+    match x.get(pos) {
+        Some(b'.') => Ok(()),
+        _ => Err(RowDefect::InvalidMeasurement),
+    }
+}
+
+fn digit(b: u8) -> Result<u8, RowDefect> {
+    if b.is_ascii_digit() {
+        Ok(b - b'0')
+    } else {
+        Err(RowDefect::InvalidMeasurement)
+    }
+}
+
+/// Validating counterpart to `parse_float`: same two-digit-plus-one-decimal
+/// format, but every assumption the fast path makes is checked instead of
+/// trusted.
+pub(crate) fn try_parse_float(x: &[u8]) -> Result<f64, RowDefect> {
+    if x.is_empty() {
+        return Err(RowDefect::InvalidMeasurement);
+    }
+    let neg = x[0] == b'-';
+    let len = x.len();
+
+    let (d1, d2, d3) = match (neg, len) {
+        (false, 3) => {
+            check_dot(x, 1)?;
+            (0, digit(x[0])?, digit(x[2])?)
+        }
+        (false, 4) => {
+            check_dot(x, 2)?;
+            (digit(x[0])?, digit(x[1])?, digit(x[3])?)
+        }
+        (true, 4) => {
+            check_dot(x, 2)?;
+            (0, digit(x[1])?, digit(x[3])?)
+        }
+        (true, 5) => {
+            check_dot(x, 3)?;
+            (digit(x[1])?, digit(x[2])?, digit(x[4])?)
+        }
+        _ => return Err(RowDefect::InvalidMeasurement),
+    };
+
+    let int = ((d1 as i64) * 100) + ((d2 as i64) * 10) + (d3 as i64);
+    let int = if neg { -int } else { int };
+    Ok((int / 10) as f64)
+}
+
+/// Validating counterpart to `parse_row`. Returns the defect instead of
+/// panicking so the caller can decide whether to skip or report the row.
+pub(crate) fn try_parse_row(data: &[u8]) -> Result<(&[u8], f64), RowDefect> {
+    if data.len() > MAX_ROW_LEN {
+        return Err(RowDefect::LineTooLong);
+    }
+    let mut split = data.split(|&c| c == b';');
+    let city = split.next().ok_or(RowDefect::MissingSeparator)?;
+    let measurement = split.next().ok_or(RowDefect::MissingSeparator)?;
+    if split.next().is_some() {
+        return Err(RowDefect::MissingSeparator);
+    }
+    if city.is_empty() {
+        return Err(RowDefect::EmptyCity);
+    }
+    let measurement = try_parse_float(measurement)?;
+    Ok((city, measurement))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_parse_float_accepts_all_sign_and_length_forms() {
+        // Mirrors `parse_float`'s own integer-division rounding, so these
+        // check agreement with the fast path rather than "mathematically
+        // exact" values.
+        assert_eq!(try_parse_float(b"1.2"), Ok(1.0));
+        assert_eq!(try_parse_float(b"12.3"), Ok(12.0));
+        assert_eq!(try_parse_float(b"-1.2"), Ok(-1.0));
+        assert_eq!(try_parse_float(b"-12.3"), Ok(-12.0));
+    }
+
+    #[test]
+    fn try_parse_float_rejects_malformed_measurements() {
+        assert_eq!(try_parse_float(b""), Err(RowDefect::InvalidMeasurement));
+        assert_eq!(try_parse_float(b"abc"), Err(RowDefect::InvalidMeasurement));
+        assert_eq!(try_parse_float(b"1,2"), Err(RowDefect::InvalidMeasurement));
+        assert_eq!(try_parse_float(b"123.4"), Err(RowDefect::InvalidMeasurement));
+    }
+
+    #[test]
+    fn try_parse_row_accepts_a_well_formed_row() {
+        assert_eq!(try_parse_row(b"Paris;12.3"), Ok((b"Paris".as_slice(), 12.0)));
+    }
+
+    #[test]
+    fn try_parse_row_rejects_missing_separator() {
+        assert_eq!(try_parse_row(b"Paris12.3"), Err(RowDefect::MissingSeparator));
+    }
+
+    #[test]
+    fn try_parse_row_rejects_extra_separators() {
+        assert_eq!(try_parse_row(b"Paris;12.3;extra"), Err(RowDefect::MissingSeparator));
+    }
+
+    #[test]
+    fn try_parse_row_rejects_empty_city() {
+        assert_eq!(try_parse_row(b";12.3"), Err(RowDefect::EmptyCity));
+    }
+
+    #[test]
+    fn try_parse_row_rejects_invalid_measurement() {
+        assert_eq!(try_parse_row(b"Paris;abc"), Err(RowDefect::InvalidMeasurement));
+    }
+
+    #[test]
+    fn try_parse_row_accepts_exactly_max_row_len() {
+        let mut row = vec![b'A'; MAX_ROW_LEN - 4];
+        row.extend_from_slice(b";1.2");
+        assert_eq!(row.len(), MAX_ROW_LEN);
+        let city = vec![b'A'; MAX_ROW_LEN - 4];
+        assert_eq!(try_parse_row(&row), Ok((city.as_slice(), 1.0)));
+    }
+
+    #[test]
+    fn try_parse_row_rejects_one_byte_over_max_row_len() {
+        let mut row = vec![b'A'; MAX_ROW_LEN - 3];
+        row.extend_from_slice(b";1.2");
+        assert_eq!(row.len(), MAX_ROW_LEN + 1);
+        assert_eq!(try_parse_row(&row), Err(RowDefect::LineTooLong));
+    }
+}